@@ -0,0 +1,111 @@
+// Copyright (c) 2016 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+const RETRY_BASE_MS: u64 = 10;
+const RETRY_MAX_MS: u64 = 500;
+const RETRY_MAX_ATTEMPTS: u32 = 5;
+
+pub fn with_retry<F>(mut attempt: F) -> io::Result<()>
+    where F: FnMut() -> io::Result<()>
+{
+    let mut delay_ms = RETRY_BASE_MS;
+    let mut last_err = None;
+    for try_num in 0..RETRY_MAX_ATTEMPTS {
+        match attempt() {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                if try_num + 1 < RETRY_MAX_ATTEMPTS {
+                    thread::sleep(Duration::from_millis(delay_ms));
+                    delay_ms = (delay_ms * 2).min(RETRY_MAX_MS);
+                }
+                last_err = Some(e);
+            }
+        }
+    }
+    Err(last_err.expect("with_retry always attempts at least once"))
+}
+
+pub fn fsync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir).and_then(|f| f.sync_all())
+}
+
+pub fn cleanup_stale_writes(dir: &Path) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path.extension().map(|ext| ext == "write").unwrap_or(false) {
+            let _ = fs::remove_file(&path);
+        }
+    }
+}
+
+pub fn to_io_error<E: fmt::Display>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("{}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn with_retry_succeeds_without_retrying() {
+        let calls = Cell::new(0);
+        let result = with_retry(|| {
+            calls.set(calls.get() + 1);
+            Ok(())
+        });
+        assert!(result.is_ok());
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn with_retry_gives_up_after_max_attempts() {
+        let calls = Cell::new(0);
+        let result = with_retry(|| {
+            calls.set(calls.get() + 1);
+            Err(io::Error::new(io::ErrorKind::Other, "nope"))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls.get(), RETRY_MAX_ATTEMPTS);
+    }
+
+    #[test]
+    fn cleanup_stale_writes_removes_only_write_suffixed_files() {
+        let dir = ::std::env::temp_dir().join(format!("habitat-atomic-test-{}-{}",
+                                                        ::std::process::id(),
+                                                        line!()));
+        fs::create_dir_all(&dir).unwrap();
+        let stale = dir.join("gossip.toml.write");
+        let keep = dir.join("gossip.toml");
+        File::create(&stale).unwrap();
+        File::create(&keep).unwrap();
+
+        cleanup_stale_writes(&dir);
+
+        assert!(!stale.exists());
+        assert!(keep.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
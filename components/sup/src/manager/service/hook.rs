@@ -0,0 +1,79 @@
+// Copyright (c) 2016 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus};
+use std::ptr;
+
+use libc;
+
+pub struct Hook {
+    path: PathBuf,
+}
+
+impl Hook {
+    pub fn new(path: PathBuf) -> Hook {
+        Hook { path: path }
+    }
+
+    pub fn run(&self,
+               service_group: &str,
+               svc_user: &str,
+               svc_group: &str)
+               -> io::Result<ExitStatus> {
+        let uid = try!(uid_by_name(svc_user));
+        let gid = try!(gid_by_name(svc_group));
+        Command::new(&self.path)
+            .arg(service_group)
+            .uid(uid)
+            .gid(gid)
+            .status()
+    }
+}
+
+fn uid_by_name(name: &str) -> io::Result<u32> {
+    let cname = try!(CString::new(name).map_err(invalid_name));
+    let mut pwd: libc::passwd = unsafe { mem::zeroed() };
+    let mut result: *mut libc::passwd = ptr::null_mut();
+    let mut buf = vec![0i8; 16_384];
+    let rc = unsafe {
+        libc::getpwnam_r(cname.as_ptr(), &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result)
+    };
+    if rc != 0 || result.is_null() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("no such user: {}", name)));
+    }
+    Ok(pwd.pw_uid)
+}
+
+fn gid_by_name(name: &str) -> io::Result<u32> {
+    let cname = try!(CString::new(name).map_err(invalid_name));
+    let mut grp: libc::group = unsafe { mem::zeroed() };
+    let mut result: *mut libc::group = ptr::null_mut();
+    let mut buf = vec![0i8; 16_384];
+    let rc = unsafe {
+        libc::getgrnam_r(cname.as_ptr(), &mut grp, buf.as_mut_ptr(), buf.len(), &mut result)
+    };
+    if rc != 0 || result.is_null() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("no such group: {}", name)));
+    }
+    Ok(grp.gr_gid)
+}
+
+fn invalid_name(e: ::std::ffi::NulError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, e)
+}
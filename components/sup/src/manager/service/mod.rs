@@ -12,7 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod atomic;
 pub mod config;
+pub mod hook;
+pub mod resources;
 
 use std;
 use std::fmt;
@@ -21,6 +24,7 @@ use std::io::prelude::*;
 use std::path::Path;
 use std::sync::Arc;
 use std::sync::mpsc::{sync_channel, SyncSender, Receiver, TryRecvError};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use ansi_term::Colour::{Yellow, Red, Green};
 use hcore::package::PackageIdent;
@@ -37,7 +41,10 @@ use error::Result;
 use health_check;
 use manager::signals;
 use manager::census::CensusList;
+use manager::service::atomic;
 use manager::service::config::ServiceConfig;
+use manager::service::hook::Hook;
+use manager::service::resources;
 use package::Package;
 use supervisor::{Supervisor, RuntimeConfig};
 use util;
@@ -52,6 +59,17 @@ enum LastRestartDisplay {
     ElectionFinished,
 }
 
+#[derive(Debug, PartialEq, Eq, RustcEncodable)]
+enum LastReloadDisplay {
+    None,
+    Reloaded,
+    Failed,
+}
+
+const CRASH_BACKOFF_BASE_MS: u64 = 100;
+const CRASH_BACKOFF_MAX_MS: u64 = 5 * 60 * 1000;
+const CRASH_STABILITY_MS: u64 = 60 * 1000;
+
 #[derive(Debug, RustcEncodable)]
 pub struct Service {
     pub needs_restart: bool,
@@ -59,8 +77,13 @@ pub struct Service {
     pub service_config_incarnation: Option<u64>,
     pub service_group: ServiceGroup,
     pub update_strategy: UpdateStrategy,
+    consecutive_crashes: u32,
     initialized: bool,
+    last_reload_display: LastReloadDisplay,
     last_restart_display: LastRestartDisplay,
+    last_spawn_at_ms: Option<u64>,
+    resources_applied: bool,
+    restart_not_before_ms: Option<u64>,
     supervisor: Supervisor,
     topology: Topology,
 }
@@ -78,6 +101,7 @@ impl Service {
                   &svc_group);
         let runtime_config = RuntimeConfig::new(svc_user, svc_group);
         let supervisor = Supervisor::new(package.ident().clone(), runtime_config);
+        atomic::cleanup_stale_writes(&fs::svc_path(&service_group.service));
         Ok(Service {
             service_group: service_group,
             supervisor: supervisor,
@@ -85,8 +109,13 @@ impl Service {
             topology: topology,
             needs_restart: false,
             update_strategy: update_strategy,
+            consecutive_crashes: 0,
+            last_reload_display: LastReloadDisplay::None,
             last_restart_display: LastRestartDisplay::None,
+            last_spawn_at_ms: None,
             initialized: false,
+            resources_applied: false,
+            restart_not_before_ms: None,
             service_config_incarnation: None,
         })
     }
@@ -98,10 +127,16 @@ impl Service {
     }
 
     pub fn start(&mut self) -> Result<()> {
-        self.supervisor.start()
+        try!(self.supervisor.start());
+        self.last_spawn_at_ms = Some(now_ms());
+        self.apply_resource_limits();
+        Ok(())
     }
 
     pub fn restart(&mut self, census_list: &CensusList) -> Result<()> {
+        if self.in_crash_backoff() {
+            return Ok(());
+        }
         match self.topology {
             Topology::Leader | Topology::Initializer => {
                 if let Some(census) = census_list.get(&format!("{}.{}",
@@ -134,19 +169,44 @@ impl Service {
                         }
                         self.needs_restart = false;
                         try!(self.supervisor.restart());
+                        self.teardown_resource_limits();
+                        self.last_spawn_at_ms = Some(now_ms());
+                        self.apply_resource_limits();
                     }
                 }
             }
             Topology::Standalone => {
                 self.needs_restart = false;
                 try!(self.supervisor.restart());
+                self.teardown_resource_limits();
+                self.last_spawn_at_ms = Some(now_ms());
+                self.apply_resource_limits();
             }
         }
         Ok(())
     }
 
     pub fn down(&mut self) -> Result<()> {
-        self.supervisor.down()
+        try!(self.supervisor.down());
+        self.teardown_resource_limits();
+        Ok(())
+    }
+
+    fn apply_resource_limits(&mut self) {
+        if let Some(pid) = self.supervisor.pid {
+            let limits = self.package.resource_limits();
+            if !limits.is_empty() {
+                resources::apply(&self.service_group_str(), pid, &limits);
+                self.resources_applied = true;
+            }
+        }
+    }
+
+    fn teardown_resource_limits(&mut self) {
+        if self.resources_applied {
+            resources::teardown(&self.service_group_str());
+            self.resources_applied = false;
+        }
     }
 
     pub fn send_signal(&self, signal: u32) -> Result<()> {
@@ -163,7 +223,37 @@ impl Service {
     }
 
     pub fn check_process(&mut self) -> Result<()> {
-        self.supervisor.check_process()
+        let was_up = self.supervisor.pid.is_some();
+        try!(self.supervisor.check_process());
+        if was_up && self.supervisor.pid.is_none() {
+            self.note_crash();
+        }
+        Ok(())
+    }
+
+    fn note_crash(&mut self) {
+        let stable = self.last_spawn_at_ms
+            .map(|spawned| now_ms().saturating_sub(spawned) >= CRASH_STABILITY_MS)
+            .unwrap_or(false);
+        if stable {
+            self.consecutive_crashes = 0;
+        }
+        self.consecutive_crashes = self.consecutive_crashes.saturating_add(1);
+        let delay_ms = backoff_delay_ms(self.consecutive_crashes);
+        let delay_ms = delay_ms + jitter_ms(delay_ms);
+        self.restart_not_before_ms = Some(now_ms() + delay_ms);
+        outputln!(preamble self.service_group_str(),
+            "{} crashed {} time(s) in a row; waiting {}ms before restarting",
+            Red.bold().paint("Service"),
+            self.consecutive_crashes,
+            delay_ms);
+    }
+
+    fn in_crash_backoff(&self) -> bool {
+        match self.restart_not_before_ms {
+            Some(not_before) => now_ms() < not_before,
+            None => false,
+        }
     }
 
     pub fn write_butterfly_service_config(&mut self, config: String) -> bool {
@@ -200,23 +290,42 @@ impl Service {
                 return false;
             }
 
-            if let Err(e) = std::fs::rename(&new_filename, &on_disk_path) {
+            if let Err(e) = new_file.sync_all() {
+                outputln!(preamble self.service_group_str(),
+                    "Service configuration from butterfly failed to sync the new file: {}",
+                    Red.bold().paint(format!("{}", e)));
+                return false;
+            }
+
+            if let Err(e) = atomic::with_retry(|| {
+                std::fs::rename(&new_filename, &on_disk_path)
+            }) {
                 outputln!(preamble self.service_group_str(),
                     "Service configuration from butterfly failed to rename: {}",
                     Red.bold().paint(format!("{}", e)));
                 return false;
             }
 
-            if let Err(e) = set_owner(&on_disk_path,
-                                      &self.supervisor.runtime_config.svc_user,
-                                      &self.supervisor.runtime_config.svc_group) {
+            if let Some(dir) = on_disk_path.parent() {
+                if let Err(e) = atomic::fsync_dir(dir) {
+                    debug!("Failed to fsync {:?} after butterfly config rename: {}", dir, e);
+                }
+            }
+
+            let svc_user = &self.supervisor.runtime_config.svc_user;
+            let svc_group = &self.supervisor.runtime_config.svc_group;
+            if let Err(e) = atomic::with_retry(|| {
+                set_owner(&on_disk_path, svc_user, svc_group).map_err(atomic::to_io_error)
+            }) {
                 outputln!(preamble self.service_group_str(),
                     "Service configuration from butterfly failed to set ownership: {}",
                     Red.bold().paint(format!("{}", e)));
                 return false;
             }
 
-            if let Err(e) = set_permissions(&on_disk_path, 0o770) {
+            if let Err(e) = atomic::with_retry(|| {
+                set_permissions(&on_disk_path, 0o770).map_err(atomic::to_io_error)
+            }) {
                 outputln!(preamble self.service_group_str(),
                     "Service configuration from butterfly failed to set permissions: {}",
                     Red.bold().paint(format!("{}", e)));
@@ -263,7 +372,9 @@ impl Service {
         self.package.create_svc_path();
         match service_config.write(&self.package) {
             Ok(true) => {
-                self.needs_restart = true;
+                if !self.reload() {
+                    self.needs_restart = true;
+                }
                 match self.package.reconfigure() {
                     Ok(_) => {}
                     Err(e) => {
@@ -284,6 +395,117 @@ impl Service {
         self.package.copy_run(&service_config);
         self.package.hooks().compile_all(&service_config);
     }
+
+    fn reload(&mut self) -> bool {
+        if let Some(hook) = self.package.reload_hook() {
+            let svc_user = &self.supervisor.runtime_config.svc_user;
+            let svc_group = &self.supervisor.runtime_config.svc_group;
+            match hook.run(&self.service_group_str(), svc_user, svc_group) {
+                Ok(status) if status.success() => {
+                    self.note_reload_result(true, None);
+                    true
+                }
+                Ok(status) => {
+                    self.note_reload_result(false, Some(format!("exited with {}", status)));
+                    false
+                }
+                Err(e) => {
+                    self.note_reload_result(false, Some(format!("{}", e)));
+                    false
+                }
+            }
+        } else if let Some(signal_name) = self.package.reload_signal() {
+            match signal_from_name(&signal_name) {
+                Some(signal) => {
+                    match self.send_signal(signal) {
+                        Ok(_) => {
+                            self.note_reload_result(true, None);
+                            true
+                        }
+                        Err(e) => {
+                            self.note_reload_result(false, Some(format!("{}", e)));
+                            false
+                        }
+                    }
+                }
+                None => {
+                    self.note_reload_result(false,
+                                             Some(format!("unknown reload_signal {:?}",
+                                                           signal_name)));
+                    false
+                }
+            }
+        } else {
+            false
+        }
+    }
+
+    fn note_reload_result(&mut self, succeeded: bool, err: Option<String>) {
+        if succeeded {
+            if self.last_reload_display != LastReloadDisplay::Reloaded {
+                outputln!(preamble self.service_group_str(),
+                    "{}", Green.bold().paint("Configuration reloaded"));
+                self.last_reload_display = LastReloadDisplay::Reloaded;
+            }
+        } else if self.last_reload_display != LastReloadDisplay::Failed {
+            outputln!(preamble self.service_group_str(),
+                "Reload failed: {}",
+                Red.bold().paint(err.unwrap_or_default()));
+            self.last_reload_display = LastReloadDisplay::Failed;
+        }
+    }
+}
+
+fn signal_from_name(name: &str) -> Option<u32> {
+    match name.trim_left_matches("SIG") {
+        "HUP" => Some(1),
+        "USR1" => Some(10),
+        "USR2" => Some(12),
+        "TERM" => Some(15),
+        _ => None,
+    }
+}
+
+fn backoff_delay_ms(consecutive_crashes: u32) -> u64 {
+    let shift = (consecutive_crashes - 1).min(31);
+    CRASH_BACKOFF_BASE_MS.saturating_mul(1u64 << shift).min(CRASH_BACKOFF_MAX_MS)
+}
+
+fn now_ms() -> u64 {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    since_epoch.as_secs() * 1_000 + (since_epoch.subsec_nanos() / 1_000_000) as u64
+}
+
+fn jitter_ms(base_ms: u64) -> u64 {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let spread = base_ms / 10 + 1;
+    (since_epoch.subsec_nanos() as u64) % spread
+}
+
+impl Package {
+    fn reload_hook(&self) -> Option<Hook> {
+        let path = fs::svc_path(&self.ident().name).join("hooks").join("reload");
+        if path.is_file() { Some(Hook::new(path)) } else { None }
+    }
+
+    fn reload_signal(&self) -> Option<String> {
+        let table = match rendered_config_table(&self.ident().name) {
+            Some(table) => table,
+            None => return None,
+        };
+        table.get("reload_signal").and_then(|v| v.as_str()).map(|s| s.to_string())
+    }
+}
+
+// Reads the rendered default.toml under the service's svc path, which
+// reflects the operator's applied config, not the package's original.
+pub fn rendered_config_table(ident_name: &str) -> Option<::toml::Table> {
+    let path = fs::svc_path(ident_name).join("default.toml");
+    let mut contents = String::new();
+    if File::open(&path).and_then(|mut f| f.read_to_string(&mut contents)).is_err() {
+        return None;
+    }
+    ::toml::Parser::new(&contents).parse()
 }
 
 impl fmt::Display for Service {
@@ -291,3 +513,36 @@ impl fmt::Display for Service {
         write!(f, "{}", self.package)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{backoff_delay_ms, signal_from_name, CRASH_BACKOFF_BASE_MS, CRASH_BACKOFF_MAX_MS};
+
+    #[test]
+    fn signal_from_name_recognizes_known_signals() {
+        assert_eq!(signal_from_name("HUP"), Some(1));
+        assert_eq!(signal_from_name("SIGHUP"), Some(1));
+        assert_eq!(signal_from_name("USR1"), Some(10));
+        assert_eq!(signal_from_name("SIGUSR2"), Some(12));
+        assert_eq!(signal_from_name("TERM"), Some(15));
+    }
+
+    #[test]
+    fn signal_from_name_rejects_unknown_signals() {
+        assert_eq!(signal_from_name("BOGUS"), None);
+        assert_eq!(signal_from_name(""), None);
+    }
+
+    #[test]
+    fn backoff_delay_doubles_with_each_crash() {
+        assert_eq!(backoff_delay_ms(1), CRASH_BACKOFF_BASE_MS);
+        assert_eq!(backoff_delay_ms(2), CRASH_BACKOFF_BASE_MS * 2);
+        assert_eq!(backoff_delay_ms(3), CRASH_BACKOFF_BASE_MS * 4);
+        assert_eq!(backoff_delay_ms(4), CRASH_BACKOFF_BASE_MS * 8);
+    }
+
+    #[test]
+    fn backoff_delay_caps_at_max() {
+        assert_eq!(backoff_delay_ms(1_000), CRASH_BACKOFF_MAX_MS);
+    }
+}
@@ -0,0 +1,193 @@
+// Copyright (c) 2016 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use ansi_term::Colour::Red;
+
+use package::Package;
+
+use manager::service::rendered_config_table;
+
+static LOGKEY: &'static str = "RC";
+
+const CGROUP_ROOT: &'static str = "/sys/fs/cgroup";
+const RMDIR_INITIAL_BACKOFF_MS: u64 = 10;
+const RMDIR_MAX_BACKOFF_MS: u64 = 1000;
+const RMDIR_MAX_ATTEMPTS: u32 = 8;
+
+#[derive(Debug, Clone, Default, RustcDecodable)]
+pub struct ResourceLimits {
+    pub memory_limit: Option<u64>,
+    pub cpu_shares: Option<u64>,
+    pub cpu_quota: Option<i64>,
+    pub cpu_period: Option<u64>,
+    pub pids_max: Option<u64>,
+}
+
+impl ResourceLimits {
+    pub fn is_empty(&self) -> bool {
+        self.memory_limit.is_none() && self.cpu_shares.is_none() && self.cpu_quota.is_none() &&
+        self.pids_max.is_none()
+    }
+}
+
+impl Package {
+    pub fn resource_limits(&self) -> ResourceLimits {
+        let table = match rendered_config_table(&self.ident().name) {
+            Some(table) => table,
+            None => return ResourceLimits::default(),
+        };
+        match table.get("resources") {
+            Some(value) => ::toml::decode(value.clone()).unwrap_or_default(),
+            None => ResourceLimits::default(),
+        }
+    }
+}
+
+fn is_v2() -> bool {
+    Path::new(CGROUP_ROOT).join("cgroup.controllers").exists()
+}
+
+fn v2_path(service_group: &str) -> PathBuf {
+    Path::new(CGROUP_ROOT).join("habitat").join(service_group)
+}
+
+fn v1_path(service_group: &str, controller: &str) -> PathBuf {
+    Path::new(CGROUP_ROOT).join(controller).join("habitat").join(service_group)
+}
+
+pub fn apply(service_group: &str, pid: u32, limits: &ResourceLimits) {
+    if is_v2() {
+        apply_v2(service_group, pid, limits)
+    } else {
+        apply_v1(service_group, pid, limits)
+    }
+}
+
+fn apply_v2(service_group: &str, pid: u32, limits: &ResourceLimits) {
+    let path = v2_path(service_group);
+    if !ensure_dir(service_group, &path) {
+        return;
+    }
+    if let Some(memory_limit) = limits.memory_limit {
+        write(service_group, &path.join("memory.max"), &memory_limit.to_string());
+    }
+    if limits.cpu_quota.is_some() || limits.cpu_period.is_some() {
+        let quota = limits.cpu_quota.map(|q| q.to_string()).unwrap_or_else(|| "max".to_string());
+        let period = limits.cpu_period.unwrap_or(100_000);
+        write(service_group, &path.join("cpu.max"), &format!("{} {}", quota, period));
+    }
+    if let Some(pids_max) = limits.pids_max {
+        write(service_group, &path.join("pids.max"), &pids_max.to_string());
+    }
+    write(service_group, &path.join("cgroup.procs"), &pid.to_string());
+}
+
+fn apply_v1(service_group: &str, pid: u32, limits: &ResourceLimits) {
+    if let Some(memory_limit) = limits.memory_limit {
+        let path = v1_path(service_group, "memory");
+        if ensure_dir(service_group, &path) {
+            write(service_group, &path.join("memory.limit_in_bytes"), &memory_limit.to_string());
+            write(service_group, &path.join("cgroup.procs"), &pid.to_string());
+        }
+    }
+    if limits.cpu_shares.is_some() || limits.cpu_quota.is_some() {
+        let path = v1_path(service_group, "cpu");
+        if ensure_dir(service_group, &path) {
+            if let Some(shares) = limits.cpu_shares {
+                write(service_group, &path.join("cpu.shares"), &shares.to_string());
+            }
+            if let Some(quota) = limits.cpu_quota {
+                write(service_group, &path.join("cpu.cfs_quota_us"), &quota.to_string());
+                write(service_group,
+                      &path.join("cpu.cfs_period_us"),
+                      &limits.cpu_period.unwrap_or(100_000).to_string());
+            }
+            write(service_group, &path.join("cgroup.procs"), &pid.to_string());
+        }
+    }
+    if let Some(pids_max) = limits.pids_max {
+        let path = v1_path(service_group, "pids");
+        if ensure_dir(service_group, &path) {
+            write(service_group, &path.join("pids.max"), &pids_max.to_string());
+            write(service_group, &path.join("cgroup.procs"), &pid.to_string());
+        }
+    }
+}
+
+pub fn teardown(service_group: &str) {
+    if is_v2() {
+        remove_with_retry(service_group, &v2_path(service_group));
+    } else {
+        for controller in &["memory", "cpu", "pids"] {
+            let path = v1_path(service_group, controller);
+            if path.exists() {
+                remove_with_retry(service_group, &path);
+            }
+        }
+    }
+}
+
+fn ensure_dir(service_group: &str, path: &Path) -> bool {
+    match fs::create_dir_all(path) {
+        Ok(()) => true,
+        Err(e) => {
+            outputln!(preamble service_group,
+                "Failed to create cgroup directory {:?}: {}",
+                path, Red.bold().paint(format!("{}", e)));
+            false
+        }
+    }
+}
+
+fn write(service_group: &str, path: &Path, value: &str) {
+    match fs::OpenOptions::new().write(true).open(path) {
+        Ok(mut file) => {
+            if let Err(e) = file.write_all(value.as_bytes()) {
+                outputln!(preamble service_group,
+                    "Failed to write resource limit {:?}: {}",
+                    path, Red.bold().paint(format!("{}", e)));
+            }
+        }
+        Err(e) => {
+            outputln!(preamble service_group,
+                "Failed to open {:?} for resource limits: {}",
+                path, Red.bold().paint(format!("{}", e)));
+        }
+    }
+}
+
+fn remove_with_retry(service_group: &str, path: &Path) {
+    let mut delay_ms = RMDIR_INITIAL_BACKOFF_MS;
+    for attempt in 0..RMDIR_MAX_ATTEMPTS {
+        match fs::remove_dir(path) {
+            Ok(()) => return,
+            Err(e) => {
+                if attempt + 1 == RMDIR_MAX_ATTEMPTS {
+                    outputln!(preamble service_group,
+                        "Giving up removing cgroup {:?} after {} attempts: {}",
+                        path, RMDIR_MAX_ATTEMPTS, Red.bold().paint(format!("{}", e)));
+                    return;
+                }
+                thread::sleep(Duration::from_millis(delay_ms));
+                delay_ms = (delay_ms * 2).min(RMDIR_MAX_BACKOFF_MS);
+            }
+        }
+    }
+}